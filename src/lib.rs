@@ -0,0 +1,1083 @@
+//! Recursive `cargo clean` engine, usable as a library or via the
+//! `cargo-clean-recursive` binary.
+//!
+//! The entry point is [`Cleaner`], a builder configured with the same knobs
+//! as the CLI, whose [`Cleaner::run`] walks a directory tree and returns a
+//! structured [`CleanReport`] instead of printing progress to stderr.
+//!
+//! Built as a `[lib]` + `[[bin]]` pair against `anyhow`, `bytesize`, `clap`
+//! (`derive` feature), `globset`, `ignore`, `serde` (`derive` feature), and
+//! `serde_json`.
+
+use std::collections::VecDeque;
+use std::fs::{self, read_dir, Metadata};
+use std::io::ErrorKind;
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context, Error, Result};
+use clap::ValueEnum;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::Serialize;
+
+const DEFAULT_SKIP_DIR_NAMES: [&str; 3] = [".git", ".rustup", ".cargo"];
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Builder for a recursive `cargo clean` run.
+///
+/// ```no_run
+/// use cargo_clean_recursive::Cleaner;
+///
+/// let report = Cleaner::new()
+///     .release(true)
+///     .dry_run(true)
+///     .skip("vendor/**")
+///     .run(".")?;
+/// println!("would free {} bytes", report.total_freed_bytes);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cleaner {
+    doc: bool,
+    release: bool,
+    dry_run: bool,
+    depth: usize,
+    older_than: Option<u64>,
+    skip_patterns: Vec<String>,
+    respect_gitignore: bool,
+    io_error_handling: IoErrorHandling,
+    jobs: Option<usize>,
+}
+
+impl Default for Cleaner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cleaner {
+    /// Starts a builder with the same defaults as the CLI: full clean, no
+    /// dry-run, a search depth limit of 64. No `--skip` patterns are set yet, so
+    /// [`Cleaner::run`] falls back to skipping `.git`/`.rustup`/`.cargo`
+    /// until [`Cleaner::skip`] is called at least once.
+    pub fn new() -> Self {
+        Self {
+            doc: false,
+            release: false,
+            dry_run: false,
+            depth: 64,
+            older_than: None,
+            skip_patterns: Vec::new(),
+            respect_gitignore: false,
+            io_error_handling: IoErrorHandling::RaiseUnexpected,
+            jobs: None,
+        }
+    }
+
+    /// Deletes documents.
+    pub fn doc(mut self, doc: bool) -> Self {
+        self.doc = doc;
+        self
+    }
+
+    /// Deletes release target.
+    pub fn release(mut self, release: bool) -> Self {
+        self.release = release;
+        self
+    }
+
+    /// Measure what would be deleted without actually deleting anything.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Recursive search depth limit.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Only sweep artifact files older than the given number of days. See
+    /// the CLI's `--older-than` for the exact semantics. Unlike the CLI,
+    /// this is not rejected together with [`Cleaner::doc`] or
+    /// [`Cleaner::release`]: with either of those set, it has no effect and
+    /// the project is cleaned by spawning `cargo clean`.
+    pub fn older_than(mut self, days: u64) -> Self {
+        self.older_than = Some(days);
+        self
+    }
+
+    /// Adds a `--skip` glob pattern. Once any pattern has been added, the
+    /// built-in `.git`/`.rustup`/`.cargo` defaults no longer apply — same as
+    /// the CLI's `--skip`, patterns replace the defaults rather than
+    /// supplementing them. See [`SkipMatcher::build`] for the pattern
+    /// syntax.
+    pub fn skip(mut self, pattern: impl Into<String>) -> Self {
+        self.skip_patterns.push(pattern.into());
+        self
+    }
+
+    /// While descending, honor any `.gitignore` files encountered so
+    /// ignored directories are never scanned.
+    pub fn respect_gitignore(mut self, respect_gitignore: bool) -> Self {
+        self.respect_gitignore = respect_gitignore;
+        self
+    }
+
+    /// How to handle IO errors encountered while scanning.
+    pub fn io_error_handling(mut self, handling: IoErrorHandling) -> Self {
+        self.io_error_handling = handling;
+        self
+    }
+
+    /// Maximum number of `cargo` child processes to run at once. Defaults
+    /// to the number of available CPUs.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Recursively cleans every cargo project found under `root`, returning
+    /// a structured report instead of printing to stderr.
+    pub fn run(&self, root: impl AsRef<Path>) -> Result<CleanReport> {
+        let root = root.as_ref();
+
+        let del_mode = DeleteMode {
+            doc: self.doc,
+            release: self.release,
+            dry_run: self.dry_run,
+            older_than: self.older_than,
+        };
+
+        let skip_matcher = if self.skip_patterns.is_empty() {
+            SkipMatcher::build(DEFAULT_SKIP_DIR_NAMES.iter().copied())?
+        } else {
+            SkipMatcher::build(self.skip_patterns.iter().map(String::as_str))?
+        };
+        let ctx = ScanContext::new(root, &skip_matcher, self.respect_gitignore, self.io_error_handling);
+        let jobs = self.jobs.unwrap_or_else(default_jobs);
+
+        let projects = {
+            let dry_run = self.dry_run;
+            let mut pool = CargoJobPool::new(jobs, move |execution| resolve_clean_execution(execution, dry_run));
+            process_dir(root.to_path_buf(), self.depth, &ctx, del_mode, &mut pool)?;
+            pool.finish()
+        };
+
+        let total_freed_bytes = projects.iter().map(|p| p.freed_bytes).sum();
+
+        Ok(CleanReport {
+            projects,
+            total_freed_bytes,
+        })
+    }
+}
+
+/// Number of available CPUs, used as the default `--jobs`/[`Cleaner::jobs`]
+/// concurrency cap.
+pub(crate) fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+}
+
+/// The outcome of a [`Cleaner::run`], covering every cargo project found
+/// under the scanned root.
+#[derive(Debug, Clone, Serialize)]
+pub struct CleanReport {
+    pub projects: Vec<ProjectReport>,
+    pub total_freed_bytes: u64,
+}
+
+/// The outcome of cleaning a single cargo project.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectReport {
+    pub path: PathBuf,
+    pub freed_bytes: u64,
+    pub status: ProjectStatus,
+    /// Raw `cargo clean` output, when the project was cleaned by spawning
+    /// cargo rather than natively; for a failed project, a description of
+    /// the error when one is available.
+    pub message: Option<String>,
+}
+
+/// How a single project's cleanup was carried out, or whether it failed.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectStatus {
+    /// `target/` was removed in-process, without spawning `cargo`.
+    Native,
+    /// Cleaned by spawning `cargo clean`.
+    Spawned,
+    /// The spawned `cargo clean` failed or its output couldn't be parsed,
+    /// scanning or cleaning the directory hit an error, or `--older-than`
+    /// was set and the target dir couldn't be located natively.
+    Failed,
+}
+
+pub(crate) fn process_dir(
+    path: PathBuf,
+    depth: usize,
+    ctx: &ScanContext,
+    del_mode: DeleteMode,
+    pool: &mut CargoJobPool<ProjectReport>,
+) -> Result<()> {
+    if depth == 0 {
+        return Ok(());
+    }
+
+    if ctx.is_skipped(&path) {
+        return Ok(());
+    }
+
+    detect_and_clean(&path, del_mode, pool)
+        .with_context(|| format!("cleaning directory {}", path.display()))?;
+
+    let ctx = ctx.descend(&path)?;
+
+    let rd = match read_dir(&path)
+        .handle_io_error(ctx.io_error_handling)
+        .with_context(|| format!("reading directory {}", path.display()))?
+    {
+        ControlFlow::Continue(rd) => rd,
+        ControlFlow::Break(()) => return Ok(()),
+    };
+
+    for entry in rd {
+        let entry = match entry
+            .handle_io_error(ctx.io_error_handling)
+            .with_context(|| format!("reading directory entry {}", path.display()))?
+        {
+            ControlFlow::Continue(entry) => entry,
+            ControlFlow::Break(()) => continue,
+        };
+
+        if entry.file_type()?.is_dir() {
+            let child_path = entry.path();
+            if let Err(e) = process_dir(child_path.clone(), depth - 1, &ctx, del_mode, pool) {
+                pool.push_result(ProjectReport {
+                    path: child_path,
+                    freed_bytes: 0,
+                    status: ProjectStatus::Failed,
+                    message: Some(format!("{e:#}")),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn detect_and_clean(path: &Path, del_mode: DeleteMode, pool: &mut CargoJobPool<ProjectReport>) -> Result<()> {
+    let is_cargo_dir = path.join("Cargo.toml").is_file();
+    if !is_cargo_dir {
+        return Ok(());
+    }
+
+    // A full clean (no `--doc`, no `--release`) just deletes `target/`
+    // wholesale, so we can do it ourselves instead of paying for a `cargo
+    // clean` child process per project. Partial cleans still need cargo,
+    // since it alone knows which fingerprints belong to which profile.
+    if !del_mode.do_doc() && !del_mode.do_release() {
+        match locate_target_dir(path) {
+            Some(target_dir) => {
+                let freed_bytes = if let Some(days) = del_mode.older_than {
+                    let cutoff = age_cutoff(days);
+                    if del_mode.dry_run {
+                        NativeAgeSweepDryRun { cutoff }.finish(&target_dir)
+                    } else {
+                        NativeAgeSweep { cutoff }.finish(&target_dir)
+                    }
+                } else if del_mode.dry_run {
+                    NativeDryRun.finish(&target_dir)
+                } else {
+                    NativeRemove.finish(&target_dir)
+                }
+                .with_context(|| format!("cleaning {}", target_dir.display()))?;
+
+                pool.push_result(ProjectReport {
+                    path: path.to_path_buf(),
+                    freed_bytes,
+                    status: ProjectStatus::Native,
+                    message: None,
+                });
+                return Ok(());
+            }
+            // `--older-than` only knows how to sweep a located target dir
+            // natively: `cargo clean` has no per-file age mode, so falling
+            // through to spawning a plain `cargo clean` here would silently
+            // turn an age sweep into a full clean, wiping fresh artifacts
+            // the user asked to keep. Report the project as unreachable
+            // instead.
+            None if del_mode.older_than.is_some() => {
+                pool.push_result(ProjectReport {
+                    path: path.to_path_buf(),
+                    freed_bytes: 0,
+                    status: ProjectStatus::Failed,
+                    message: Some(
+                        "couldn't locate the target dir natively, and `--older-than` has no \
+                         `cargo clean` equivalent to fall back to"
+                            .to_string(),
+                    ),
+                });
+                return Ok(());
+            }
+            None => {}
+        }
+    }
+
+    let mut args = vec!["clean"];
+
+    if del_mode.do_release() {
+        args.push("--release");
+    }
+    if del_mode.do_doc() {
+        args.push("--doc");
+    }
+    if del_mode.dry_run {
+        args.push("--dry-run");
+    }
+
+    pool.submit(path, &args)?;
+
+    Ok(())
+}
+
+/// Locates the directory that a full `cargo clean` would remove for the
+/// project rooted at `path`, so it can be deleted natively instead of
+/// spawning `cargo`. This is always `path/target`; a workspace member whose
+/// build output lives in the workspace root's `target/` is not detected.
+///
+/// Returns `None` when the location can't be determined purely from the
+/// manifest (e.g. `CARGO_TARGET_DIR`/`CARGO_BUILD_TARGET_DIR` is set, or a
+/// `.cargo/config.toml` overrides `build.target-dir`), in which case the
+/// caller falls back to spawning a real `cargo clean`, which does honor
+/// those overrides, or, under `--older-than`, reports the project as failed.
+fn locate_target_dir(path: &Path) -> Option<PathBuf> {
+    if std::env::var_os("CARGO_TARGET_DIR").is_some()
+        || std::env::var_os("CARGO_BUILD_TARGET_DIR").is_some()
+        || has_target_dir_override(path)
+    {
+        return None;
+    }
+
+    let target_dir = path.join("target");
+    Some(target_dir)
+}
+
+/// Best-effort scan for a `build.target-dir` override in any
+/// `.cargo/config.toml`/`.cargo/config` between `path` and the filesystem
+/// root, the same directories cargo itself searches for config. This is a
+/// plain substring search rather than a full TOML parse, so it can false
+/// positive (eg. on a commented-out key). For a full clean that only costs
+/// a needless `cargo clean` spawn; under `--older-than` the project is
+/// reported as failed and not swept.
+fn has_target_dir_override(path: &Path) -> bool {
+    path.ancestors().any(|dir| {
+        [".cargo/config.toml", ".cargo/config"]
+            .iter()
+            .any(|name| fs::read_to_string(dir.join(name)).is_ok_and(|contents| contents.contains("target-dir")))
+    })
+}
+
+/// A pluggable strategy for getting rid of a single path under `target/`.
+///
+/// Splitting "how do I dispose of one path" from the recursive walk lets
+/// [`NativeRemove`] and [`NativeDryRun`] share the exact same traversal and
+/// concurrency logic, so measuring what a clean *would* free costs nothing
+/// extra to keep in sync with what it actually frees.
+trait DirectoryOp: Send + Sync {
+    /// Disposes of a single file or now-empty directory, returning the
+    /// number of bytes freed (`0` for directories and symlinks, since a
+    /// symlink's own metadata length reflects its target path, not real
+    /// disk space reclaimed by removing the link).
+    fn remove(&self, path: &Path, metadata: &Metadata) -> Result<u64>;
+
+    /// Whether [`finish`](DirectoryOp::finish) should also remove `dir`
+    /// itself once it's empty, the way a full `cargo clean` removes
+    /// `target/` entirely. Partial sweeps (eg. [`NativeAgeSweep`]) leave it
+    /// in place, since the directory is still in active use.
+    fn remove_root(&self) -> bool {
+        false
+    }
+
+    /// Recursively removes `dir`, dispatching each top-level subtree
+    /// (`debug/`, `release/`, `.fingerprint/`, ...) to its own worker
+    /// thread so independent subtrees are torn down concurrently, and
+    /// returns the summed bytes freed.
+    fn finish(&self, dir: &Path) -> Result<u64>
+    where
+        Self: Sized,
+    {
+        let rd = match read_dir(dir) {
+            Ok(rd) => rd,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => {
+                return Err(Error::from(e))
+                    .with_context(|| format!("reading directory {}", dir.display()))
+            }
+        };
+
+        let total = AtomicU64::new(0);
+
+        thread::scope(|scope| -> Result<()> {
+            let mut handles = Vec::new();
+
+            for entry in rd {
+                let entry = entry
+                    .with_context(|| format!("reading directory entry {}", dir.display()))?;
+                handles.push(scope.spawn(move || self.remove_subtree(&entry.path())));
+            }
+
+            for handle in handles {
+                let freed = handle
+                    .join()
+                    .map_err(|_| anyhow!("a cleanup worker thread panicked"))??;
+                total.fetch_add(freed, Ordering::Relaxed);
+            }
+
+            Ok(())
+        })?;
+
+        if self.remove_root() {
+            let metadata = fs::symlink_metadata(dir)
+                .with_context(|| format!("reading metadata of {}", dir.display()))?;
+            self.remove(dir, &metadata)?;
+        }
+
+        Ok(total.load(Ordering::Relaxed))
+    }
+
+    /// Walks a single subtree depth-first, disposing of files on the way
+    /// back up and then the now-empty directory that contained them.
+    fn remove_subtree(&self, path: &Path) -> Result<u64>
+    where
+        Self: Sized,
+    {
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("reading metadata of {}", path.display()))?;
+
+        if metadata.is_dir() {
+            let mut freed = 0;
+            for entry in
+                read_dir(path).with_context(|| format!("reading directory {}", path.display()))?
+            {
+                let entry = entry
+                    .with_context(|| format!("reading directory entry {}", path.display()))?;
+                freed += self.remove_subtree(&entry.path())?;
+            }
+            freed += self.remove(path, &metadata)?;
+            Ok(freed)
+        } else {
+            self.remove(path, &metadata)
+        }
+    }
+}
+
+/// Bytes freed by disposing of a single non-directory path: `0` for a
+/// symlink, since `metadata.len()` on `symlink_metadata` reflects the
+/// link's own (tiny) size, not the data it points at.
+fn freed_size(metadata: &Metadata) -> u64 {
+    if metadata.is_symlink() {
+        0
+    } else {
+        metadata.len()
+    }
+}
+
+/// Actually deletes paths from disk.
+struct NativeRemove;
+
+impl DirectoryOp for NativeRemove {
+    fn remove(&self, path: &Path, metadata: &Metadata) -> Result<u64> {
+        if metadata.is_dir() {
+            fs::remove_dir(path)
+                .with_context(|| format!("removing directory {}", path.display()))?;
+            Ok(0)
+        } else {
+            fs::remove_file(path)
+                .with_context(|| format!("removing file {}", path.display()))?;
+            Ok(freed_size(metadata))
+        }
+    }
+
+    fn remove_root(&self) -> bool {
+        true
+    }
+}
+
+/// Measures what [`NativeRemove`] would free without touching the
+/// filesystem. Backs `--dry-run` for full cleans.
+struct NativeDryRun;
+
+impl DirectoryOp for NativeDryRun {
+    fn remove(&self, _path: &Path, metadata: &Metadata) -> Result<u64> {
+        Ok(if metadata.is_dir() { 0 } else { freed_size(metadata) })
+    }
+
+    fn remove_root(&self) -> bool {
+        true
+    }
+}
+
+/// Deletes only artifact files whose most recent access-or-modification
+/// time is before `cutoff`, and prunes directories that become empty as a
+/// result. Fresh artifacts, and any directory still holding one, are left
+/// untouched so incremental rebuilds stay fast. `cutoff` of `None` (an
+/// out-of-range `--older-than`) means nothing is treated as stale.
+struct NativeAgeSweep {
+    cutoff: Option<SystemTime>,
+}
+
+impl DirectoryOp for NativeAgeSweep {
+    fn remove(&self, path: &Path, metadata: &Metadata) -> Result<u64> {
+        if metadata.is_dir() {
+            let is_empty = read_dir(path)
+                .with_context(|| format!("reading directory {}", path.display()))?
+                .next()
+                .is_none();
+            if is_empty {
+                fs::remove_dir(path)
+                    .with_context(|| format!("removing directory {}", path.display()))?;
+            }
+            Ok(0)
+        } else if is_stale(metadata, self.cutoff) {
+            fs::remove_file(path)
+                .with_context(|| format!("removing file {}", path.display()))?;
+            Ok(freed_size(metadata))
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+/// Measures what [`NativeAgeSweep`] would free without touching the
+/// filesystem. Backs `--dry-run --older-than`.
+struct NativeAgeSweepDryRun {
+    cutoff: Option<SystemTime>,
+}
+
+impl DirectoryOp for NativeAgeSweepDryRun {
+    fn remove(&self, _path: &Path, metadata: &Metadata) -> Result<u64> {
+        if metadata.is_dir() {
+            Ok(0)
+        } else if is_stale(metadata, self.cutoff) {
+            Ok(freed_size(metadata))
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+/// Whether a file's most recent access or modification is older than
+/// `cutoff`. Always `false` when `cutoff` is `None`.
+fn is_stale(metadata: &Metadata, cutoff: Option<SystemTime>) -> bool {
+    let Some(cutoff) = cutoff else {
+        return false;
+    };
+
+    let last_used = metadata.accessed().ok().into_iter().chain(metadata.modified().ok()).max();
+
+    match last_used {
+        Some(last_used) => last_used < cutoff,
+        None => false,
+    }
+}
+
+/// The age cutoff for `--older-than DAYS`: `now - DAYS days`, or `None` if
+/// that's out of range (`DAYS * SECS_PER_DAY` overflows `u64`, or the
+/// result would be before what `SystemTime` can represent), in which case
+/// the caller should treat nothing as stale rather than panic.
+fn age_cutoff(days: u64) -> Option<SystemTime> {
+    let secs = days.checked_mul(SECS_PER_DAY)?;
+    SystemTime::now().checked_sub(Duration::from_secs(secs))
+}
+
+/// Spawns `cargo <args>` (eg. `["clean", "--release"]`, or `["update"]` for
+/// the generic `--` command runner) in `current_dir`.
+fn spawn_cargo(current_dir: &Path, args: &[&str]) -> Result<CargoExecution> {
+    let child = Command::new("cargo")
+        .args(args)
+        .current_dir(current_dir)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to spawn `cargo {}`", args.join(" ")))?;
+
+    Ok(CargoExecution {
+        child,
+        path: current_dir.to_path_buf(),
+    })
+}
+
+/// A spawned `cargo` child process, not yet waited on.
+#[derive(Debug)]
+pub struct CargoExecution {
+    child: Child,
+    pub path: PathBuf,
+}
+
+impl CargoExecution {
+    /// Non-blocking check for whether the child has already exited.
+    fn try_wait(&mut self) -> std::io::Result<Option<process::ExitStatus>> {
+        self.child.try_wait()
+    }
+
+    /// Waits for the child to finish, returning its captured stdout/stderr.
+    pub fn wait_with_output(self) -> std::io::Result<process::Output> {
+        self.child.wait_with_output()
+    }
+}
+
+/// Bounds how many `cargo` children run at once, resolving each into a `T`
+/// (eg. a [`ProjectReport`] for `cargo clean`, or a failure path for an
+/// arbitrary `--` command) as soon as it's reaped.
+///
+/// Callers used to spawn every child immediately and collect them in an
+/// unbounded `Vec`, forking one `cargo` invocation per discovered project
+/// before waiting on any of them. This instead keeps at most `max_jobs`
+/// children alive, reaping one — preferring any that has already exited
+/// over blocking on the oldest still-running one — before a new one is
+/// spawned.
+pub struct CargoJobPool<T> {
+    max_jobs: usize,
+    in_flight: VecDeque<CargoExecution>,
+    resolve: Box<dyn FnMut(CargoExecution) -> T>,
+    results: Vec<T>,
+}
+
+impl<T> CargoJobPool<T> {
+    pub fn new(max_jobs: usize, resolve: impl FnMut(CargoExecution) -> T + 'static) -> Self {
+        Self {
+            max_jobs: max_jobs.max(1),
+            in_flight: VecDeque::new(),
+            resolve: Box::new(resolve),
+            results: Vec::new(),
+        }
+    }
+
+    /// Records a result that didn't go through the child-process
+    /// concurrency cap (eg. a native clean, or a recursion error).
+    pub fn push_result(&mut self, result: T) {
+        self.results.push(result);
+    }
+
+    /// Spawns `cargo <args>` in `path`, first reaping a finished in-flight
+    /// child if we're already at the concurrency cap.
+    pub fn submit(&mut self, path: &Path, args: &[&str]) -> Result<()> {
+        if self.in_flight.len() >= self.max_jobs {
+            self.reap_one();
+        }
+        self.in_flight.push_back(spawn_cargo(path, args)?);
+        Ok(())
+    }
+
+    /// Reaps a child that has already exited (a non-blocking poll), falling
+    /// back to blocking on the oldest one only if none have finished yet —
+    /// so one slow `cargo` invocation doesn't stall reaping of others that
+    /// are already done.
+    fn reap_one(&mut self) {
+        let finished = self
+            .in_flight
+            .iter_mut()
+            .position(|execution| matches!(execution.try_wait(), Ok(Some(_))));
+
+        let execution = match finished {
+            Some(index) => self.in_flight.remove(index).expect("index came from iter_mut"),
+            None => self.in_flight.pop_front().expect("called with a non-empty queue"),
+        };
+
+        let result = (self.resolve)(execution);
+        self.results.push(result);
+    }
+
+    /// Waits out every remaining in-flight child, returning every result
+    /// recorded so far.
+    pub fn finish(mut self) -> Vec<T> {
+        while !self.in_flight.is_empty() {
+            self.reap_one();
+        }
+        self.results
+    }
+}
+
+/// Waits for a spawned `cargo clean` to finish and extracts the bytes it
+/// freed from its stderr.
+fn resolve_clean_execution(execution: CargoExecution, dry_run: bool) -> ProjectReport {
+    let path = execution.path.clone();
+
+    match execution.wait_with_output() {
+        Ok(output) => {
+            // We only care if the command was successfully finished.
+            // Cargo may fail to clean due to various reasons.
+            //   (eg. too old format version of Cargo.toml, missing permission, etc.)
+            if !output.status.success() {
+                return ProjectReport {
+                    path,
+                    freed_bytes: 0,
+                    status: ProjectStatus::Failed,
+                    message: None,
+                };
+            }
+
+            // cargo clean's output gets piped to stdout for some reason
+            let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+
+            // Get the first line of the cargo's output.
+            let first_line = message
+                .split_once('\n')
+                .map(|(first_line, _)| first_line)
+                .unwrap_or(&message);
+
+            // If project is already clean, we don't need to parse size.
+            let zero_line = if dry_run { "Summary 0 files" } else { "Removed 0 files" };
+            if first_line == zero_line {
+                return ProjectReport {
+                    path,
+                    freed_bytes: 0,
+                    status: ProjectStatus::Spawned,
+                    message: Some(message),
+                };
+            }
+
+            // upon a non-empty cargo clean, we find how much data was removed.
+            // The 3rd item is the data amount (eg 7MiB)
+            //
+            // Example cargo's output:
+            //   Removed 2020 files, 986.5MiB total
+            let size = first_line
+                .split_whitespace()
+                .nth(3)
+                .map(bytesize::ByteSize::from_str);
+
+            match size {
+                Some(Ok(size)) => ProjectReport {
+                    path,
+                    freed_bytes: size.0,
+                    status: ProjectStatus::Spawned,
+                    message: Some(message),
+                },
+                _ => ProjectReport {
+                    path,
+                    freed_bytes: 0,
+                    status: ProjectStatus::Failed,
+                    message: Some(format!("failed to parse size of cargo clean output: {first_line}")),
+                },
+            }
+        }
+        // Erors may occur if the child process was started but not finished.
+        // We can't do anything about it.
+        Err(e) => ProjectReport {
+            path,
+            freed_bytes: 0,
+            status: ProjectStatus::Failed,
+            message: Some(e.to_string()),
+        },
+    }
+}
+
+/// A compiled set of `--skip` glob patterns, tested against a scanned
+/// directory's path relative to the scan root.
+pub struct SkipMatcher {
+    globs: GlobSet,
+}
+
+impl SkipMatcher {
+    /// Compiles `patterns` once up front. Each pattern is expanded into a
+    /// few equivalent variants so it behaves the way a user typing a
+    /// `--skip` glob would expect:
+    /// - a pattern with no `/` also matches at any depth, the same way the
+    ///   old exact-name skip list did (eg. `.git` also adds `**/.git`);
+    /// - a `dir/**` pattern also skips `dir` itself, not just its contents,
+    ///   and when `dir` has no `/`, also a `dir` at any depth (eg.
+    ///   `vendor/**` also adds `vendor` and `**/vendor`).
+    ///
+    /// `literal_separator` is set so a single `*`/`?` matches within one
+    /// path component only (eg. `foo*` matches `foobar` but not
+    /// `foo/bar`); `**` still crosses components regardless.
+    pub fn build<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            for variant in skip_pattern_variants(pattern) {
+                builder.add(
+                    GlobBuilder::new(&variant)
+                        .literal_separator(true)
+                        .build()
+                        .with_context(|| format!("invalid --skip pattern {pattern:?}"))?,
+                );
+            }
+        }
+
+        let globs = builder.build().context("compiling --skip patterns")?;
+        Ok(Self { globs })
+    }
+
+    pub fn is_match(&self, rel_path: &Path) -> bool {
+        self.globs.is_match(rel_path)
+    }
+}
+
+/// Expands a single `--skip` pattern into its equivalent variants; see
+/// [`SkipMatcher::build`].
+fn skip_pattern_variants(pattern: &str) -> Vec<String> {
+    let mut variants = vec![pattern.to_string()];
+
+    if !pattern.contains('/') {
+        variants.push(format!("**/{pattern}"));
+    }
+
+    if let Some(base) = pattern.strip_suffix("/**") {
+        variants.push(base.to_string());
+        if !base.contains('/') {
+            variants.push(format!("**/{base}"));
+        }
+    }
+
+    variants
+}
+
+/// Scan-wide options that stay the same as we recurse, plus the stack of
+/// `.gitignore` matchers picked up on the way down from `root`.
+pub struct ScanContext<'a> {
+    root: &'a Path,
+    skip_matcher: &'a SkipMatcher,
+    respect_gitignore: bool,
+    io_error_handling: IoErrorHandling,
+    gitignores: Vec<Gitignore>,
+}
+
+impl<'a> ScanContext<'a> {
+    pub fn new(
+        root: &'a Path,
+        skip_matcher: &'a SkipMatcher,
+        respect_gitignore: bool,
+        io_error_handling: IoErrorHandling,
+    ) -> Self {
+        Self {
+            root,
+            skip_matcher,
+            respect_gitignore,
+            io_error_handling,
+            gitignores: Vec::new(),
+        }
+    }
+
+    /// How IO errors encountered while scanning should be handled.
+    pub fn io_error_handling(&self) -> IoErrorHandling {
+        self.io_error_handling
+    }
+
+    /// Whether `path` (always a directory in this scanner) should be
+    /// skipped: it matches a `--skip` glob, or it's ignored by a
+    /// `.gitignore` encountered on the way down.
+    pub fn is_skipped(&self, path: &Path) -> bool {
+        let rel = path.strip_prefix(self.root).unwrap_or(path);
+        if self.skip_matcher.is_match(rel) {
+            return true;
+        }
+
+        self.respect_gitignore
+            && self
+                .gitignores
+                .iter()
+                .any(|gitignore| gitignore.matched(path, true).is_ignore())
+    }
+
+    /// Returns the context to use for `dir`'s children, picking up its own
+    /// `.gitignore` if `--respect-gitignore` is set and it has one.
+    pub fn descend(&self, dir: &Path) -> Result<Self> {
+        let mut gitignores = self.gitignores.clone();
+
+        if self.respect_gitignore {
+            let gitignore_path = dir.join(".gitignore");
+            if gitignore_path.is_file() {
+                let mut builder = GitignoreBuilder::new(dir);
+                if let Some(e) = builder.add(&gitignore_path) {
+                    return Err(Error::from(e))
+                        .with_context(|| format!("reading {}", gitignore_path.display()));
+                }
+                gitignores.push(
+                    builder
+                        .build()
+                        .with_context(|| format!("compiling {}", gitignore_path.display()))?,
+                );
+            }
+        }
+
+        Ok(Self {
+            root: self.root,
+            skip_matcher: self.skip_matcher,
+            respect_gitignore: self.respect_gitignore,
+            io_error_handling: self.io_error_handling,
+            gitignores,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DeleteMode {
+    doc: bool,
+    release: bool,
+    dry_run: bool,
+    older_than: Option<u64>,
+}
+
+impl DeleteMode {
+    fn do_doc(self) -> bool {
+        self.doc
+    }
+
+    fn do_release(self) -> bool {
+        self.release
+    }
+}
+
+/// How to handle IO errors.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum IoErrorHandling {
+    /// Ignore All IO errors.
+    Ignore,
+
+    /// Show only unexpected IO errors.
+    ///
+    /// For examples, "Permission denied" is an expected error.
+    /// It may occur when the program tries to read a file that
+    /// the user doesn't have permission to read.
+    RaiseUnexpected,
+
+    /// Print all IO errors.
+    RaiseAll,
+}
+
+pub trait IoErrorHandlingExt<T> {
+    fn handle_io_error(self, handling: IoErrorHandling) -> Result<ControlFlow<(), T>>;
+}
+
+impl<T> IoErrorHandlingExt<T> for std::result::Result<T, std::io::Error> {
+    fn handle_io_error(self, handling: IoErrorHandling) -> Result<ControlFlow<(), T>> {
+        match self {
+            Ok(v) => Ok(ControlFlow::Continue(v)),
+            Err(e) => match handling {
+                IoErrorHandling::Ignore => Ok(ControlFlow::Break(())),
+                IoErrorHandling::RaiseUnexpected => match e.kind() {
+                    ErrorKind::PermissionDenied => Ok(ControlFlow::Break(())),
+                    _ => Err(Error::from(e)),
+                },
+                IoErrorHandling::RaiseAll => Err(Error::from(e)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{File, FileTimes};
+
+    #[test]
+    fn skip_pattern_variants_expand_bare_names_to_any_depth() {
+        assert_eq!(skip_pattern_variants(".git"), vec![".git", "**/.git"]);
+    }
+
+    #[test]
+    fn skip_pattern_variants_expand_dir_star_star_to_the_dir_itself() {
+        assert_eq!(
+            skip_pattern_variants("dir/**"),
+            vec!["dir/**", "dir", "**/dir"]
+        );
+    }
+
+    #[test]
+    fn skip_pattern_variants_expand_bare_dir_star_star_at_any_depth_too() {
+        assert_eq!(
+            skip_pattern_variants("vendor/**"),
+            vec!["vendor/**", "vendor", "**/vendor"]
+        );
+    }
+
+    #[test]
+    fn skip_pattern_variants_leave_nested_patterns_alone() {
+        assert_eq!(skip_pattern_variants("target/debug"), vec!["target/debug"]);
+    }
+
+    #[test]
+    fn skip_matcher_matches_bare_name_at_any_depth() {
+        let matcher = SkipMatcher::build([".git"]).unwrap();
+        assert!(matcher.is_match(Path::new(".git")));
+        assert!(matcher.is_match(Path::new("nested/.git")));
+        assert!(!matcher.is_match(Path::new("not-git")));
+    }
+
+    #[test]
+    fn skip_matcher_matches_dir_star_star_including_the_dir_itself() {
+        let matcher = SkipMatcher::build(["vendor/**"]).unwrap();
+        assert!(matcher.is_match(Path::new("vendor")));
+        assert!(matcher.is_match(Path::new("vendor/crate")));
+    }
+
+    #[test]
+    fn skip_matcher_single_star_does_not_cross_path_components() {
+        let matcher = SkipMatcher::build(["*.bak"]).unwrap();
+        assert!(matcher.is_match(Path::new("foo.bak")));
+        assert!(matcher.is_match(Path::new("nested/foo.bak")));
+        assert!(!matcher.is_match(Path::new("foo.bak/nested")));
+
+        let matcher = SkipMatcher::build(["foo*"]).unwrap();
+        assert!(matcher.is_match(Path::new("foobar")));
+        assert!(!matcher.is_match(Path::new("foo/bar")));
+    }
+
+    fn unique_temp_dir(label: &str) -> PathBuf {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("cargo-clean-recursive-test-{label}-{n}-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn backdate(path: &Path, age: Duration) {
+        let time = SystemTime::now().checked_sub(age).unwrap();
+        let times = FileTimes::new().set_accessed(time).set_modified(time);
+        File::open(path).unwrap().set_times(times).unwrap();
+    }
+
+    #[test]
+    fn native_age_sweep_removes_only_stale_files_and_prunes_emptied_dirs() {
+        let root = unique_temp_dir("age-sweep");
+
+        let stale_dir = root.join("stale-only");
+        fs::create_dir(&stale_dir).unwrap();
+        let stale_file = stale_dir.join("old.o");
+        File::create(&stale_file).unwrap();
+        backdate(&stale_file, Duration::from_secs(2 * SECS_PER_DAY));
+
+        let mixed_dir = root.join("mixed");
+        fs::create_dir(&mixed_dir).unwrap();
+        let fresh_file = mixed_dir.join("fresh.o");
+        File::create(&fresh_file).unwrap();
+
+        let cutoff = age_cutoff(1);
+        NativeAgeSweep { cutoff }.finish(&root).unwrap();
+
+        assert!(!stale_dir.exists(), "emptied directory should be pruned");
+        assert!(mixed_dir.exists(), "directory with a surviving file should remain");
+        assert!(fresh_file.exists(), "fresh file should not be swept");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn age_cutoff_returns_none_instead_of_panicking_on_extreme_input() {
+        assert!(age_cutoff(u64::MAX).is_none());
+    }
+}
@@ -1,13 +1,15 @@
-use std::collections::HashSet;
 use std::env::{args, current_dir};
-use std::fs::read_dir;
-use std::io::ErrorKind;
+use std::num::NonZeroUsize;
 use std::ops::ControlFlow;
-use std::path::{Path, PathBuf};
-use std::process::{self, Child, Command};
-use std::str::FromStr;
-
-use anyhow::{Context, Error, Result};
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+
+use anyhow::{Context, Result};
+use cargo_clean_recursive::{
+    CargoExecution, CargoJobPool, Cleaner, IoErrorHandling, IoErrorHandlingExt, ProjectStatus, ScanContext,
+    SkipMatcher,
+};
 use clap::{Parser, ValueEnum};
 
 const DEFAULT_SKIP_DIR_NAMES: [&str; 3] = [".git", ".rustup", ".cargo"];
@@ -39,9 +41,25 @@ struct Args {
     #[clap(long, default_value_t = 64)]
     depth: usize,
 
-    /// Skip scan directories with specified names. (if empty, '.git' '.rustup' '.cargo')
+    /// Only sweep artifact files whose most recent access or modification
+    /// time is older than the given number of days, leaving freshly-used
+    /// artifacts in place. Cannot be combined with `--doc`/`--release`,
+    /// since cargo itself has no per-file age mode.
+    #[clap(long, value_name = "DAYS", conflicts_with_all = ["doc", "release"])]
+    older_than: Option<u64>,
+
+    /// Skip scan directories matching the given glob pattern(s), tested
+    /// against each directory's path relative to the scan root (eg.
+    /// `--skip 'vendor/**'`, `--skip '*.bak'`). A pattern with no `/`, or of
+    /// the form `dir/**` with no other `/`, also matches at any depth. (if
+    /// empty, '.git' '.rustup' '.cargo')
+    #[clap(long)]
+    skip: Option<Vec<String>>,
+
+    /// While descending, honor any `.gitignore` files encountered so
+    /// ignored directories are never scanned.
     #[clap(long)]
-    skips: Option<Vec<String>>,
+    respect_gitignore: bool,
 
     /// How to handle IO errors.
     #[clap(long, default_value = "raise-unexpected")]
@@ -51,149 +69,193 @@ struct Args {
     #[clap(short = 'v', long)]
     verbose: bool,
 
+    /// Output format for the clean report.
+    #[clap(long, default_value = "text")]
+    format: OutputFormat,
+
     /// Target directory
     path: Option<PathBuf>,
+
+    /// Runs `cargo <CARGO_ARGS>...` in every discovered project instead of
+    /// cleaning (eg. `cargo clean-recursive -- update`, `-- build --release`).
+    /// When given, all clean-related flags above are ignored.
+    #[clap(last = true)]
+    cargo_args: Vec<String>,
+
+    /// Maximum number of `cargo` child processes to run at once.
+    /// Defaults to the number of available CPUs.
+    #[clap(short = 'j', long)]
+    jobs: Option<usize>,
+}
+
+/// Output format for a clean run; the generic `--` command runner always
+/// reports in text, since it has no structured report to emit as JSON.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable progress and summary on stderr.
+    Text,
+    /// The full `CleanReport`, as JSON, on stdout.
+    Json,
 }
 
 impl Args {
     fn run(&self) -> Result<()> {
-        let delete_mode = DeleteMode {
-            doc: self.doc,
-            release: self.release,
-            dry_run: self.dry_run,
-        };
+        if !self.cargo_args.is_empty() {
+            return self.run_cargo_command();
+        }
 
-        let skips: HashSet<String> = if let Some(ref skips) = self.skips {
-            skips.iter().cloned().collect()
-        } else {
-            let mut skips = HashSet::new();
-            for n in DEFAULT_SKIP_DIR_NAMES {
-                skips.insert(n.to_string());
-            }
-            skips
-        };
+        let path = self.path()?;
 
-        let depth = self.depth;
+        let mut cleaner = Cleaner::new()
+            .doc(self.doc)
+            .release(self.release)
+            .dry_run(self.dry_run)
+            .depth(self.depth)
+            .respect_gitignore(self.respect_gitignore)
+            .io_error_handling(self.io_error_handling)
+            .jobs(self.jobs());
 
-        let path = if let Some(path) = self.path.clone() {
-            path
-        } else {
-            current_dir().context("getting current_dir")?
-        };
+        for pattern in self.skip_patterns() {
+            cleaner = cleaner.skip(pattern);
+        }
+        if let Some(days) = self.older_than {
+            cleaner = cleaner.older_than(days);
+        }
 
-        let mut executions = Vec::new();
-
-        process_dir(
-            path,
-            depth,
-            &skips,
-            delete_mode,
-            self.io_error_handling,
-            &mut executions,
-        )?;
-
-        let mut sum = bytesize::ByteSize::b(0);
-
-        // Wait for all children to finish and sum up the space saved
-        for CargoCleanExecution { child, path } in executions {
-            match child.wait_with_output() {
-                Ok(output) => {
-                    // We only care if the command was successfully finished.
-                    // Cargo may fail to clean due to various reasons.
-                    //   (eg. too old format version of Cargo.toml, missing permission, etc.)
-                    // We don't care about them.
-                    if output.status.success() {
-                        // cargo clean's output gets piped to stdout for some reason
-                        let output = String::from_utf8_lossy(&output.stderr);
-                        let output = output.trim();
-
-                        // If verbose mode is enabled, print the output.
-                        if self.verbose {
-                            eprintln!("==== {} ====\n{}", path.display(), output);
-                        }
+        let report = cleaner.run(&path)?;
 
-                        // Get the first line of the cargo's output.
-                        let output = output
-                            .split_once('\n')
-                            .map(|(first_line, _)| first_line)
-                            .unwrap_or(output);
-
-                        // If project is already clean, we don't need to parse size.
-                        if self.dry_run {
-                            // If cargo prints "Summary 0 files", we don't need to parse it.
-                            if output == "Summary 0 files" {
-                                continue;
-                            }
-                        } else {
-                            // If cargo prints "Removed 0 files", we don't need to parse it.
-                            if output == "Removed 0 files" {
-                                continue;
-                            }
+        match self.format {
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+            OutputFormat::Text => {
+                for project in &report.projects {
+                    match project.status {
+                        ProjectStatus::Failed => {
+                            eprintln!(
+                                "==== {} ====\nFailed: {}",
+                                project.path.display(),
+                                project.message.as_deref().unwrap_or("unknown error")
+                            );
                         }
-
-                        // upon a non-empty cargo clean, we find how much data was removed.
-                        // The 3rd item is the data amount (eg 7MiB)
-                        //
-                        // Example cargo's output:
-                        //   Removed 2020 files, 986.5MiB total
-                        let size = output
-                            .split_whitespace()
-                            .nth(3)
-                            .map(bytesize::ByteSize::from_str);
-
-                        match size {
-                            Some(Ok(size)) => {
-                                sum += size;
-                            }
-                            _ => {
-                                eprintln!("Failed to parse size of cargo clean output: {}", output);
+                        ProjectStatus::Native | ProjectStatus::Spawned => {
+                            if self.verbose {
+                                if let Some(message) = &project.message {
+                                    eprintln!("==== {} ====\n{}", project.path.display(), message);
+                                } else if project.freed_bytes > 0 {
+                                    eprintln!(
+                                        "==== {} ====\nRemoved {}",
+                                        project.path.display(),
+                                        bytesize::ByteSize::b(project.freed_bytes)
+                                    );
+                                }
                             }
                         }
                     }
                 }
-                // If we failed to get the output, we just print the error.
-                //
-                // Erors may occur if the child process was started but not finished.
-                // We can't do anything about it.
-                Err(e) => {
-                    eprintln!("Failed to get child process output: {}", e);
+
+                let total = bytesize::ByteSize::b(report.total_freed_bytes);
+                if self.dry_run {
+                    eprintln!("Total space that will be saved: {total}");
+                } else {
+                    eprintln!("Total space saved: {total}");
                 }
             }
         }
 
-        if self.dry_run {
-            eprintln!("Total space that will be saved: {sum}");
+        Ok(())
+    }
+
+    /// Runs `cargo <cargo_args>` in every discovered project instead of
+    /// cleaning, and reports which (if any) invocations failed.
+    fn run_cargo_command(&self) -> Result<()> {
+        let skip_matcher = self.skip_matcher()?;
+        let depth = self.depth;
+        let path = self.path()?;
+        let ctx = ScanContext::new(&path, &skip_matcher, self.respect_gitignore, self.io_error_handling);
+
+        let args: Vec<&str> = self.cargo_args.iter().map(String::as_str).collect();
+
+        let verbose = self.verbose;
+        let failed: Vec<PathBuf> = {
+            let mut pool = CargoJobPool::new(self.jobs(), move |execution| {
+                resolve_command_execution(execution, verbose)
+            });
+
+            process_dir_for_command(path.clone(), depth, &ctx, &args, &mut pool)?;
+
+            pool.finish().into_iter().flatten().collect()
+        };
+
+        if failed.is_empty() {
+            eprintln!("cargo {} succeeded in every project.", args.join(" "));
+            Ok(())
         } else {
-            eprintln!("Total space saved: {sum}");
+            eprintln!("cargo {} failed in {} project(s):", args.join(" "), failed.len());
+            for path in &failed {
+                eprintln!("  {}", path.display());
+            }
+            process::exit(1);
         }
+    }
 
-        Ok(())
+    /// Concurrency cap for spawned `cargo` children: `--jobs`, or the
+    /// number of available CPUs if unset.
+    fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(NonZeroUsize::get)
+                .unwrap_or(1)
+        })
+    }
+
+    fn skip_patterns(&self) -> Vec<String> {
+        self.skip.clone().unwrap_or_default()
+    }
+
+    fn skip_matcher(&self) -> Result<SkipMatcher> {
+        match self.skip {
+            Some(ref patterns) => SkipMatcher::build(patterns.iter().map(String::as_str)),
+            None => SkipMatcher::build(DEFAULT_SKIP_DIR_NAMES.iter().copied()),
+        }
+    }
+
+    fn path(&self) -> Result<PathBuf> {
+        if let Some(path) = self.path.clone() {
+            Ok(path)
+        } else {
+            current_dir().context("getting current_dir")
+        }
     }
 }
 
-fn process_dir(
+fn process_dir_for_command(
     path: PathBuf,
     depth: usize,
-    skips: &HashSet<String>,
-    del_mode: DeleteMode,
-    io_error_handling: IoErrorHandling,
-    executions: &mut Vec<CargoCleanExecution>,
+    ctx: &ScanContext,
+    cargo_args: &[&str],
+    pool: &mut CargoJobPool<Option<PathBuf>>,
 ) -> Result<()> {
     if depth == 0 {
         return Ok(());
     }
 
-    if let Some(Some(dir_name)) = path.file_name().map(|n| n.to_str()) {
-        if skips.contains(dir_name) {
-            return Ok(());
+    if ctx.is_skipped(&path) {
+        return Ok(());
+    }
+
+    if path.join("Cargo.toml").is_file() {
+        eprintln!("Running in {:?}", path);
+        if let Err(e) = pool.submit(&path, cargo_args) {
+            pool.push_result(Some(path.clone()));
+            return Err(e);
         }
     }
 
-    detect_and_clean(&path, del_mode, executions)
-        .with_context(|| format!("cleaning directory {}", path.display()))?;
+    let ctx = ctx.descend(&path)?;
 
-    let rd = match read_dir(&path)
-        .handle_io_error(io_error_handling)
+    let rd = match std::fs::read_dir(&path)
+        .handle_io_error(ctx.io_error_handling())
         .with_context(|| format!("reading directory {}", path.display()))?
     {
         ControlFlow::Continue(rd) => rd,
@@ -202,7 +264,7 @@ fn process_dir(
 
     for entry in rd {
         let entry = match entry
-            .handle_io_error(io_error_handling)
+            .handle_io_error(ctx.io_error_handling())
             .with_context(|| format!("reading directory entry {}", path.display()))?
         {
             ControlFlow::Continue(entry) => entry,
@@ -210,14 +272,9 @@ fn process_dir(
         };
 
         if entry.file_type()?.is_dir() {
-            if let Err(e) = process_dir(
-                entry.path(),
-                depth - 1,
-                skips,
-                del_mode,
-                io_error_handling,
-                executions,
-            ) {
+            if let Err(e) =
+                process_dir_for_command(entry.path(), depth - 1, &ctx, cargo_args, pool)
+            {
                 eprintln!("{:#}", e);
             }
         }
@@ -226,108 +283,29 @@ fn process_dir(
     Ok(())
 }
 
-fn detect_and_clean(
-    path: &Path,
-    del_mode: DeleteMode,
-    executions: &mut Vec<CargoCleanExecution>,
-) -> Result<()> {
-    let is_cargo_dir = path.join("Cargo.toml").is_file();
-    if !is_cargo_dir {
-        return Ok(());
-    }
-
-    eprintln!("Checking {:?}", path);
-
-    let mut args = Vec::<&'static str>::new();
-
-    if del_mode.do_release() {
-        args.push("--release");
-    }
-    if del_mode.do_doc() {
-        args.push("--doc");
-    }
-    if del_mode.dry_run {
-        args.push("--dry-run");
-    }
-
-    executions.push(spawn_cargo_clean(path, &args)?);
-
-    Ok(())
-}
-
-fn spawn_cargo_clean(current_dir: &Path, args: &[&str]) -> Result<CargoCleanExecution> {
-    let child = Command::new("cargo")
-        .arg("clean")
-        .args(args)
-        .current_dir(current_dir)
-        .stdin(process::Stdio::null())
-        .stdout(process::Stdio::null())
-        .stderr(process::Stdio::piped())
-        .spawn()
-        .context("failed to spawn `cargo clean`")?;
-
-    Ok(CargoCleanExecution {
-        child,
-        path: current_dir.to_path_buf(),
-    })
-}
-
-#[derive(Debug)]
-struct CargoCleanExecution {
-    child: Child,
-    path: PathBuf,
-}
-
-#[derive(Debug, Clone, Copy)]
-struct DeleteMode {
-    doc: bool,
-    release: bool,
-    dry_run: bool,
-}
-
-impl DeleteMode {
-    fn do_doc(self) -> bool {
-        self.doc
-    }
-
-    fn do_release(self) -> bool {
-        self.release
-    }
-}
-
-/// How to handle IO errors.
-#[derive(Debug, Clone, Copy, ValueEnum)]
-enum IoErrorHandling {
-    /// Ignore All IO errors.
-    Ignore,
-
-    /// Show only unexpected IO errors.
-    ///
-    /// For examples, "Permission denied" is an expected error.
-    /// It may occur when the program tries to read a file that
-    /// the user doesn't have permission to read.
-    RaiseUnexpected,
-
-    /// Print all IO errors.
-    RaiseAll,
-}
-
-trait IoErrorHandlingExt<T> {
-    fn handle_io_error(self, handling: IoErrorHandling) -> Result<ControlFlow<(), T>>;
-}
+/// Waits for a spawned `cargo <args>` to finish, printing its combined
+/// output when `verbose` or it failed, and returns its project path if it
+/// failed.
+fn resolve_command_execution(execution: CargoExecution, verbose: bool) -> Option<PathBuf> {
+    let path = execution.path.clone();
+
+    match execution.wait_with_output() {
+        Ok(output) => {
+            if verbose || !output.status.success() {
+                let mut combined = String::from_utf8_lossy(&output.stdout).into_owned();
+                combined.push_str(&String::from_utf8_lossy(&output.stderr));
+                eprintln!("==== {} ====\n{}", path.display(), combined.trim());
+            }
 
-impl<T> IoErrorHandlingExt<T> for std::result::Result<T, std::io::Error> {
-    fn handle_io_error(self, handling: IoErrorHandling) -> Result<ControlFlow<(), T>> {
-        match self {
-            Ok(v) => Ok(ControlFlow::Continue(v)),
-            Err(e) => match handling {
-                IoErrorHandling::Ignore => Ok(ControlFlow::Break(())),
-                IoErrorHandling::RaiseUnexpected => match e.kind() {
-                    ErrorKind::PermissionDenied => Ok(ControlFlow::Break(())),
-                    _ => Err(Error::from(e)),
-                },
-                IoErrorHandling::RaiseAll => Err(Error::from(e)),
-            },
+            if output.status.success() {
+                None
+            } else {
+                Some(path)
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to get child process output: {}", e);
+            Some(path)
         }
     }
 }